@@ -1,6 +1,7 @@
 // #![deny(warnings, missing_docs)]
 // #![cfg_attr(test, feature(plugin))]
 // #![cfg_attr(test, plugin(clippy))]
+#![allow(clippy::redundant_field_names)]
 
 //! Parse [Dat protocol SLEEP
 //! files](https://github.com/datproject/docs/blob/master/papers/sleep.md).
@@ -20,15 +21,23 @@
 //!   <8 byte Uint64BE children leaf byte length>
 //! ```
 
+extern crate blake2;
+extern crate ed25519_dalek;
+#[cfg(test)]
+extern crate rand;
+
 use std::error::Error;
+use std::fs::{File, OpenOptions};
 use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
 macro_rules! bail {
   ($msg: expr) => {
-    return Err(Box::new(io::Error::new(
-      io::ErrorKind::Other,
+    return Err(Box::new($crate::io::Error::new(
+      $crate::io::ErrorKind::Other,
       $msg,
-    )));
+    )))
   };
 }
 
@@ -40,7 +49,12 @@ macro_rules! ensure {
   };
 }
 
+pub mod register;
+pub mod signatures;
+pub mod tree;
+
 /// Algorithm used for hashing the data.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum HashAlgorithm {
   /// [BLAKE2b](https://blake2.net/) hashing algorithm.
   BLAKE2b,
@@ -49,6 +63,7 @@ pub enum HashAlgorithm {
 }
 
 /// Type of file.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum FileType {
   BitField,
   Signatures,
@@ -56,11 +71,13 @@ pub enum FileType {
 }
 
 /// SLEEP Protocol version.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Version {
   V0,
 }
 
 /// Struct representation of 32 byte SLEEP headers.
+#[derive(Debug, PartialEq)]
 pub struct Header {
   pub file_type: FileType,
   pub version: Version,
@@ -69,15 +86,22 @@ pub struct Header {
 }
 
 impl Header {
+  /// Build a new `Header` from its constituent parts.
   pub fn new(
-    tree_type: FileType,
+    file_type: FileType,
     entry_size: u16,
     hash_algorithm: HashAlgorithm,
-  ) {
+  ) -> Header {
+    Header {
+      version: Version::V0,
+      file_type: file_type,
+      entry_size: entry_size,
+      hash_algorithm: hash_algorithm,
+    }
   }
 
   /// Parse a 32 bit buffer into a valid Header type.
-  pub fn from_vec(buffer: &Vec<u8>) -> Result<Header, Box<Error>> {
+  pub fn from_vec(buffer: &[u8]) -> Result<Header, Box<dyn Error>> {
     ensure!(
       buffer.len() == 32,
       "buffer should be at least 32 bytes"
@@ -105,26 +129,273 @@ impl Header {
       )),
     };
 
+    let entry_size = (u16::from(buffer[5]) << 8) | u16::from(buffer[6]);
+    ensure!(entry_size > 0, "entry size cannot be zero");
+
+    let algorithm_name_length = buffer[7] as usize;
+    ensure!(
+      algorithm_name_length <= 24,
+      "the algorithm name length prefix cannot be greater than 24, since \
+       only 24 bytes remain in the header"
+    );
+
+    let name_end = 8 + algorithm_name_length;
+    let algorithm_name = match String::from_utf8(buffer[8..name_end].to_vec()) {
+      Ok(name) => name,
+      Err(_) => bail!("the algorithm name is not valid UTF-8"),
+    };
+
+    let hash_algorithm = match algorithm_name.as_str() {
+      "BLAKE2b" => HashAlgorithm::BLAKE2b,
+      "Ed25519" => HashAlgorithm::Ed25519,
+      name => bail!(format!(
+        "'{}' does not belong to any known hash algorithm",
+        name
+      )),
+    };
+
+    for &byte in &buffer[name_end..32] {
+      ensure!(byte == 0, "the header padding should be all zeroes");
+    }
+
     Ok(Header {
       version: Version::V0,
-      entry_size: 40,
+      entry_size: entry_size,
       file_type: file_type,
-      hash_algorithm: HashAlgorithm::BLAKE2b,
+      hash_algorithm: hash_algorithm,
     })
   }
 
   /// Convert a `Header` into a `Vec<u8>`. Use this to persist a header back to
   /// disk.
-  pub fn to_vec(&self) {}
+  pub fn to_vec(&self) -> Vec<u8> {
+    let mut buffer = vec![0; 32];
+
+    buffer[0] = 5;
+    buffer[1] = 2;
+    buffer[2] = 87;
+    buffer[3] = match self.file_type {
+      FileType::BitField => 0,
+      FileType::Signatures => 1,
+      FileType::Tree => 2,
+    };
+    buffer[4] = match self.version {
+      Version::V0 => 0,
+    };
+    buffer[5] = (self.entry_size >> 8) as u8;
+    buffer[6] = self.entry_size as u8;
+
+    let name = match self.hash_algorithm {
+      HashAlgorithm::BLAKE2b => "BLAKE2b",
+      HashAlgorithm::Ed25519 => "Ed25519",
+    };
+    buffer[7] = name.len() as u8;
+    buffer[8..8 + name.len()].copy_from_slice(name.as_bytes());
+
+    buffer
+  }
 }
 
 #[test]
-fn test() {
-  use std::fs::File;
-  use std::io::{BufRead, BufReader};
-
-  let file = File::open("README.md").unwrap();
-  let mut reader = BufReader::with_capacity(40, file);
-  let buffer = reader.fill_buf().unwrap();
-  println!("{:?}", buffer.len());
+fn test_header_round_trip() {
+  let h = Header::new(FileType::Tree, 40, HashAlgorithm::BLAKE2b);
+  assert!(Header::from_vec(&h.to_vec()).unwrap() == h);
+
+  let h = Header::new(FileType::Signatures, 64, HashAlgorithm::Ed25519);
+  assert!(Header::from_vec(&h.to_vec()).unwrap() == h);
+}
+
+/// Build a raw 32 byte header buffer with an arbitrary algorithm name, for
+/// exercising `from_vec`'s failure paths directly.
+#[cfg(test)]
+fn header_bytes(entry_size: u16, algorithm_name: &[u8]) -> Vec<u8> {
+  let mut buffer = vec![0; 32];
+  buffer[0] = 5;
+  buffer[1] = 2;
+  buffer[2] = 87;
+  buffer[3] = 2; // FileType::Tree
+  buffer[5] = (entry_size >> 8) as u8;
+  buffer[6] = entry_size as u8;
+  buffer[7] = algorithm_name.len() as u8;
+  buffer[8..8 + algorithm_name.len()].copy_from_slice(algorithm_name);
+  buffer
+}
+
+#[test]
+fn test_from_vec_rejects_zero_entry_size() {
+  let buffer = header_bytes(0, b"BLAKE2b");
+  assert!(Header::from_vec(&buffer).is_err());
+}
+
+#[test]
+fn test_from_vec_rejects_unknown_algorithm() {
+  let buffer = header_bytes(40, b"Unknown");
+  assert!(Header::from_vec(&buffer).is_err());
+}
+
+#[test]
+fn test_from_vec_rejects_non_utf8_algorithm_name() {
+  let buffer = header_bytes(40, &[0xff]);
+  assert!(Header::from_vec(&buffer).is_err());
+}
+
+#[test]
+fn test_from_vec_rejects_oversized_algorithm_name_length() {
+  let mut buffer = header_bytes(40, b"BLAKE2b");
+  buffer[7] = 25;
+  assert!(Header::from_vec(&buffer).is_err());
+}
+
+#[test]
+fn test_from_vec_rejects_nonzero_padding() {
+  let mut buffer = header_bytes(40, b"BLAKE2b");
+  buffer[31] = 1;
+  assert!(Header::from_vec(&buffer).is_err());
+}
+
+/// Random-access storage for the fixed-size entries that follow a SLEEP
+/// header.
+pub trait SleepStorage {
+  /// Read the entry at `index`.
+  fn read(&mut self, index: u64) -> Result<Vec<u8>, Box<dyn Error>>;
+
+  /// Write `data` as the entry at `index`. `data` must be exactly
+  /// `entry_size` bytes long.
+  fn write(&mut self, index: u64, data: &[u8]) -> Result<(), Box<dyn Error>>;
+
+  /// Number of entries currently stored.
+  fn len(&mut self) -> Result<u64, Box<dyn Error>>;
+
+  /// Whether there are no entries currently stored.
+  fn is_empty(&mut self) -> Result<bool, Box<dyn Error>> {
+    Ok(self.len()? == 0)
+  }
+
+  /// Size in bytes of a single entry.
+  fn entry_size(&self) -> u16;
+
+  /// Hash algorithm used by the entries in this file.
+  fn hash_algorithm(&self) -> &HashAlgorithm;
+}
+
+/// A SLEEP file backed by a `std::fs::File`.
+pub struct SleepFile {
+  file: File,
+  header: Header,
+}
+
+impl SleepFile {
+  /// Open an existing SLEEP file at `path`, reading and validating its
+  /// header.
+  pub fn open<P: AsRef<Path>>(
+    path: P,
+    writable: bool,
+  ) -> Result<SleepFile, Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(writable)
+      .open(path)?;
+
+    let mut buffer = vec![0; 32];
+    file.read_exact(&mut buffer)?;
+    let header = Header::from_vec(&buffer)?;
+
+    Ok(SleepFile {
+      file: file,
+      header: header,
+    })
+  }
+
+  /// The file type recorded in this file's header.
+  pub fn file_type(&self) -> &FileType {
+    &self.header.file_type
+  }
+
+  /// The SLEEP protocol version recorded in this file's header.
+  pub fn version(&self) -> &Version {
+    &self.header.version
+  }
+}
+
+impl SleepStorage for SleepFile {
+  fn read(&mut self, index: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let entry_size = self.entry_size() as u64;
+    let offset = 32 + index * entry_size;
+
+    let mut buffer = vec![0; entry_size as usize];
+    self.file.seek(SeekFrom::Start(offset))?;
+    self.file.read_exact(&mut buffer)?;
+    Ok(buffer)
+  }
+
+  fn write(&mut self, index: u64, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    ensure!(
+      data.len() == self.entry_size() as usize,
+      "data length does not match entry size"
+    );
+
+    let offset = 32 + index * self.entry_size() as u64;
+    self.file.seek(SeekFrom::Start(offset))?;
+    self.file.write_all(data)?;
+    Ok(())
+  }
+
+  fn len(&mut self) -> Result<u64, Box<dyn Error>> {
+    let file_len = self.file.metadata()?.len();
+    Ok((file_len - 32) / self.entry_size() as u64)
+  }
+
+  fn entry_size(&self) -> u16 {
+    self.header.entry_size
+  }
+
+  fn hash_algorithm(&self) -> &HashAlgorithm {
+    &self.header.hash_algorithm
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static TEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+  fn temp_file_path() -> PathBuf {
+    let id = TEST_COUNTER.fetch_add(1, Ordering::SeqCst);
+    ::std::env::temp_dir().join(format!("sleep-parser-test-{}-{}", ::std::process::id(), id))
+  }
+
+  fn write_sleep_file(path: &Path) {
+    let header = Header::new(FileType::Tree, 40, HashAlgorithm::BLAKE2b);
+    ::std::fs::write(path, header.to_vec()).unwrap();
+  }
+
+  #[test]
+  fn test_sleep_file_round_trip() {
+    let path = temp_file_path();
+    write_sleep_file(&path);
+
+    let mut sleep_file = SleepFile::open(&path, true).unwrap();
+    assert_eq!(sleep_file.len().unwrap(), 0);
+
+    let entry = vec![7; 40];
+    sleep_file.write(0, &entry).unwrap();
+    assert_eq!(sleep_file.len().unwrap(), 1);
+    assert_eq!(sleep_file.read(0).unwrap(), entry);
+
+    ::std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_sleep_file_write_rejects_wrong_length() {
+    let path = temp_file_path();
+    write_sleep_file(&path);
+
+    let mut sleep_file = SleepFile::open(&path, true).unwrap();
+    assert!(sleep_file.write(0, &[0; 39]).is_err());
+
+    ::std::fs::remove_file(&path).unwrap();
+  }
 }
\ No newline at end of file
@@ -0,0 +1,205 @@
+//! Group the related SLEEP files that make up one half (`metadata.*` or
+//! `content.*`) of a Dat feed.
+
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::{FileType, HashAlgorithm, SleepFile, SleepStorage};
+
+/// The set of SLEEP files backing one side of a Dat feed: its `.tree`,
+/// `.data`, `.signatures` and `.bitfield` files.
+pub struct Register {
+  pub tree: SleepFile,
+  pub data: File,
+  pub signatures: SleepFile,
+  pub bitfield: SleepFile,
+
+  /// Cumulative byte offsets of each leaf already looked up by
+  /// `get_data_block`, so a sequential scan over a feed costs O(n) tree
+  /// reads rather than O(n^2). `leaf_offsets[i]` is the offset of leaf `i`.
+  leaf_offsets: Vec<u64>,
+}
+
+fn check_header(
+  file: &SleepFile,
+  file_type: FileType,
+  hash_algorithm: HashAlgorithm,
+) -> Result<(), Box<dyn Error>> {
+  ensure!(
+    *file.file_type() == file_type,
+    "file does not have the expected SLEEP file type"
+  );
+  ensure!(
+    *file.hash_algorithm() == hash_algorithm,
+    "file does not have the expected hash algorithm"
+  );
+  Ok(())
+}
+
+impl Register {
+  /// Open `{base_name}.tree`, `.data`, `.signatures` and `.bitfield` inside
+  /// `dir`, checking that their headers agree with each other and with the
+  /// SLEEP format expected of each file type.
+  pub fn open<P: AsRef<Path>>(
+    dir: P,
+    base_name: &str,
+    writable: bool,
+  ) -> Result<Register, Box<dyn Error>> {
+    let dir = dir.as_ref();
+
+    let tree = SleepFile::open(dir.join(format!("{}.tree", base_name)), writable)?;
+    let signatures =
+      SleepFile::open(dir.join(format!("{}.signatures", base_name)), writable)?;
+    let bitfield =
+      SleepFile::open(dir.join(format!("{}.bitfield", base_name)), writable)?;
+    let data = OpenOptions::new()
+      .read(true)
+      .write(writable)
+      .open(dir.join(format!("{}.data", base_name)))?;
+
+    check_header(&tree, FileType::Tree, HashAlgorithm::BLAKE2b)?;
+    check_header(&signatures, FileType::Signatures, HashAlgorithm::Ed25519)?;
+    check_header(&bitfield, FileType::BitField, HashAlgorithm::BLAKE2b)?;
+
+    ensure!(
+      tree.version() == signatures.version() && tree.version() == bitfield.version(),
+      "tree, signatures and bitfield headers do not agree on version"
+    );
+
+    Ok(Register {
+      tree: tree,
+      data: data,
+      signatures: signatures,
+      bitfield: bitfield,
+      leaf_offsets: vec![0],
+    })
+  }
+
+  /// Whether the bitfield marks leaf `index` as present.
+  ///
+  /// This checks a single bit per block, packed across the bitfield file's
+  /// entries; it does not implement Dat's run-length-encoded bitfield pages,
+  /// only the presence semantics needed to gate `get_data_block`.
+  fn has_block(&mut self, index: u64) -> Result<bool, Box<dyn Error>> {
+    let bits_per_entry = u64::from(self.bitfield.entry_size()) * 8;
+    let entry = self.bitfield.read(index / bits_per_entry)?;
+    let bit_offset = (index % bits_per_entry) as usize;
+    let byte = entry[bit_offset / 8];
+    Ok(byte & (1 << (7 - bit_offset % 8)) != 0)
+  }
+
+  /// Byte length of leaf `index`, read from its tree entry.
+  fn leaf_length(&mut self, index: u64) -> Result<u64, Box<dyn Error>> {
+    let entry = self.tree.read(2 * index)?;
+    let mut length = 0u64;
+    for &byte in &entry[32..40] {
+      length = (length << 8) | u64::from(byte);
+    }
+    Ok(length)
+  }
+
+  /// Byte offset of leaf `index` into the data file, extending
+  /// `leaf_offsets` as needed so this lookup reads each leaf's length at
+  /// most once.
+  fn leaf_offset(&mut self, index: u64) -> Result<u64, Box<dyn Error>> {
+    while (self.leaf_offsets.len() as u64) <= index {
+      let leaf = self.leaf_offsets.len() as u64 - 1;
+      let offset = self.leaf_offsets[leaf as usize] + self.leaf_length(leaf)?;
+      self.leaf_offsets.push(offset);
+    }
+    Ok(self.leaf_offsets[index as usize])
+  }
+
+  /// Read the raw data block at leaf `index`, cross-referencing the
+  /// bitfield (for presence) and the tree (for the block's length and
+  /// offset into the data file).
+  pub fn get_data_block(&mut self, index: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    ensure!(
+      self.has_block(index)?,
+      "block is not marked present in the bitfield"
+    );
+
+    let offset = self.leaf_offset(index)?;
+    let length = self.leaf_length(index)?;
+
+    let mut buffer = vec![0; length as usize];
+    self.data.seek(SeekFrom::Start(offset))?;
+    self.data.read_exact(&mut buffer)?;
+    Ok(buffer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::{Path, PathBuf};
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::super::tree::leaf_hash;
+  use super::super::Header;
+
+  static TEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+  fn temp_dir() -> PathBuf {
+    let id = TEST_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let dir = ::std::env::temp_dir()
+      .join(format!("sleep-parser-test-{}-{}", ::std::process::id(), id));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn leaf_entry(data: &[u8]) -> Vec<u8> {
+    let mut entry = leaf_hash(data).unwrap();
+    entry.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, data.len() as u8]);
+    entry
+  }
+
+  fn write_register(dir: &Path, base_name: &str) {
+    let leaves: [&[u8]; 2] = [b"hello", b"world!"];
+
+    let mut tree_bytes = Header::new(FileType::Tree, 40, HashAlgorithm::BLAKE2b).to_vec();
+    tree_bytes.extend(leaf_entry(leaves[0]));
+    tree_bytes.extend(vec![0; 40]);
+    tree_bytes.extend(leaf_entry(leaves[1]));
+    ::std::fs::write(dir.join(format!("{}.tree", base_name)), tree_bytes).unwrap();
+
+    let mut data_bytes = Vec::new();
+    data_bytes.extend_from_slice(leaves[0]);
+    data_bytes.extend_from_slice(leaves[1]);
+    ::std::fs::write(dir.join(format!("{}.data", base_name)), data_bytes).unwrap();
+
+    let signatures_bytes =
+      Header::new(FileType::Signatures, 64, HashAlgorithm::Ed25519).to_vec();
+    ::std::fs::write(dir.join(format!("{}.signatures", base_name)), signatures_bytes).unwrap();
+
+    let mut bitfield_bytes =
+      Header::new(FileType::BitField, 4, HashAlgorithm::BLAKE2b).to_vec();
+    bitfield_bytes.extend(vec![0b1100_0000, 0, 0, 0]);
+    ::std::fs::write(dir.join(format!("{}.bitfield", base_name)), bitfield_bytes).unwrap();
+  }
+
+  #[test]
+  fn test_get_data_block() {
+    let dir = temp_dir();
+    write_register(&dir, "metadata");
+
+    let mut register = Register::open(&dir, "metadata", false).unwrap();
+    assert_eq!(register.get_data_block(0).unwrap(), b"hello");
+    assert_eq!(register.get_data_block(1).unwrap(), b"world!");
+
+    ::std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_check_header_rejects_wrong_type() {
+    let dir = temp_dir();
+    write_register(&dir, "metadata");
+
+    let tree = SleepFile::open(dir.join("metadata.tree"), false).unwrap();
+    assert!(check_header(&tree, FileType::Signatures, HashAlgorithm::BLAKE2b).is_err());
+
+    ::std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
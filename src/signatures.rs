@@ -0,0 +1,111 @@
+//! Ed25519 signature verification for Signatures (`.signatures`) files.
+
+use std::error::Error;
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+
+use super::{HashAlgorithm, SleepStorage};
+
+/// Verify that the signature stored for tree `length` was produced by the
+/// holder of `public_key` over `root_hash`.
+///
+/// The signature for a given tree length is stored at entry `length - 1` of
+/// the signatures file. Returns an error if the file's `HashAlgorithm` is
+/// not `Ed25519`, and `Ok(false)` (not an error) if the signature simply
+/// does not validate.
+pub fn verify<S: SleepStorage>(
+  storage: &mut S,
+  public_key: &[u8; 32],
+  length: u64,
+  root_hash: &[u8],
+) -> Result<bool, Box<dyn Error>> {
+  match *storage.hash_algorithm() {
+    HashAlgorithm::Ed25519 => {}
+    _ => bail!("signatures file does not use the Ed25519 algorithm"),
+  }
+
+  ensure!(length > 0, "tree length must be at least 1");
+
+  let entry = storage.read(length - 1)?;
+  ensure!(entry.len() == 64, "signature entries should be 64 bytes");
+
+  let public_key = match PublicKey::from_bytes(public_key) {
+    Ok(public_key) => public_key,
+    Err(err) => bail!(format!("invalid Ed25519 public key: {}", err)),
+  };
+  let signature = match Signature::from_bytes(&entry) {
+    Ok(signature) => signature,
+    Err(err) => bail!(format!("invalid Ed25519 signature: {}", err)),
+  };
+
+  Ok(public_key.verify(root_hash, &signature).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ed25519_dalek::{Keypair, Signer};
+  use rand::rngs::OsRng;
+
+  struct MemoryStorage {
+    entries: Vec<Vec<u8>>,
+  }
+
+  impl SleepStorage for MemoryStorage {
+    fn read(&mut self, index: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+      Ok(self.entries[index as usize].clone())
+    }
+
+    fn write(&mut self, index: u64, data: &[u8]) -> Result<(), Box<dyn Error>> {
+      self.entries[index as usize] = data.to_vec();
+      Ok(())
+    }
+
+    fn len(&mut self) -> Result<u64, Box<dyn Error>> {
+      Ok(self.entries.len() as u64)
+    }
+
+    fn entry_size(&self) -> u16 {
+      64
+    }
+
+    fn hash_algorithm(&self) -> &HashAlgorithm {
+      &HashAlgorithm::Ed25519
+    }
+  }
+
+  #[test]
+  fn test_verify_accepts_valid_signature() {
+    let keypair = Keypair::generate(&mut OsRng {});
+    let root_hash = b"some root hash";
+    let signature = keypair.sign(root_hash);
+
+    let mut storage = MemoryStorage {
+      entries: vec![signature.to_bytes().to_vec()],
+    };
+
+    let public_key = keypair.public.to_bytes();
+    assert!(verify(&mut storage, &public_key, 1, root_hash).unwrap());
+  }
+
+  #[test]
+  fn test_verify_rejects_wrong_signature() {
+    let keypair = Keypair::generate(&mut OsRng {});
+    let other = Keypair::generate(&mut OsRng {});
+    let root_hash = b"some root hash";
+    let signature = other.sign(root_hash);
+
+    let mut storage = MemoryStorage {
+      entries: vec![signature.to_bytes().to_vec()],
+    };
+
+    let public_key = keypair.public.to_bytes();
+    assert!(!verify(&mut storage, &public_key, 1, root_hash).unwrap());
+  }
+
+  #[test]
+  fn test_verify_rejects_zero_length() {
+    let mut storage = MemoryStorage { entries: vec![] };
+    assert!(verify(&mut storage, &[0; 32], 0, b"x").is_err());
+  }
+}
@@ -0,0 +1,224 @@
+//! Merkle/flat-tree verification for Tree (`.tree`) files.
+//!
+//! Dat lays entries out using an in-order "flat tree" numbering: leaves
+//! (the hashes of raw data chunks) sit at the even indices, and each odd
+//! index holds the hash of the two subtrees below it. A node's depth is
+//! the number of trailing one-bits in its index, so index `0` is a leaf
+//! (depth 0), index `1` is its parent (depth 1), index `3` is the parent
+//! of `1` and `5` (depth 2), and so on.
+
+use std::error::Error;
+
+use blake2::digest::{Input, VariableOutput};
+use blake2::VarBlake2b;
+
+use super::SleepStorage;
+
+/// A parsed Tree-file entry: a node's hash and the total byte length of
+/// the leaves beneath it (or, for a leaf, the length of its own data).
+pub struct Node {
+  pub hash: Vec<u8>,
+  pub length: u64,
+}
+
+/// Depth of `index` in the flat tree (0 for leaves).
+fn depth(index: u64) -> u32 {
+  (!index).trailing_zeros()
+}
+
+/// The two children of the parent node at `index`, given its `depth`.
+fn children(index: u64, depth: u32) -> (u64, u64) {
+  let span = 1u64 << (depth - 1);
+  (index - span, index + span)
+}
+
+fn uint64be(value: u64) -> [u8; 8] {
+  let mut buffer = [0; 8];
+  for (i, byte) in buffer.iter_mut().enumerate() {
+    *byte = (value >> (8 * (7 - i))) as u8;
+  }
+  buffer
+}
+
+fn blake2b_256(parts: &[&[u8]]) -> Result<Vec<u8>, Box<dyn Error>> {
+  let mut hasher = match VarBlake2b::new(32) {
+    Ok(hasher) => hasher,
+    Err(_) => bail!("failed to construct a 32 byte BLAKE2b hasher"),
+  };
+  for part in parts {
+    hasher.input(part);
+  }
+
+  let mut hash = vec![0; 32];
+  hasher.variable_result(|result| hash.copy_from_slice(result));
+  Ok(hash)
+}
+
+/// Hash a leaf's raw data into the value stored at its tree entry.
+pub fn leaf_hash(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+  blake2b_256(&[&[0], &uint64be(data.len() as u64), data])
+}
+
+/// Hash a parent node from its two children.
+pub fn parent_hash(left: &Node, right: &Node) -> Result<Vec<u8>, Box<dyn Error>> {
+  blake2b_256(&[
+    &[1],
+    &uint64be(left.length + right.length),
+    &left.hash,
+    &right.hash,
+  ])
+}
+
+fn read_node<S: SleepStorage>(
+  storage: &mut S,
+  index: u64,
+) -> Result<Node, Box<dyn Error>> {
+  let entry = storage.read(index)?;
+  ensure!(entry.len() == 40, "tree entries should be 40 bytes");
+
+  let mut length = 0u64;
+  for &byte in &entry[32..40] {
+    length = (length << 8) | u64::from(byte);
+  }
+
+  Ok(Node {
+    hash: entry[0..32].to_vec(),
+    length: length,
+  })
+}
+
+/// Recompute the hash and length of the parent node at `index` from its two
+/// children and compare them against what is stored there.
+pub fn verify_node<S: SleepStorage>(
+  storage: &mut S,
+  index: u64,
+) -> Result<bool, Box<dyn Error>> {
+  let node_depth = depth(index);
+  ensure!(
+    node_depth > 0,
+    "leaf nodes have no children to verify against"
+  );
+
+  let (left_index, right_index) = children(index, node_depth);
+  let node = read_node(storage, index)?;
+  let left = read_node(storage, left_index)?;
+  let right = read_node(storage, right_index)?;
+
+  let expected_length = left.length + right.length;
+  let expected_hash = parent_hash(&left, &right)?;
+
+  Ok(node.length == expected_length && node.hash == expected_hash)
+}
+
+/// The root node indices whose subtrees exactly tile a tree of `length`
+/// leaves.
+pub fn roots(length: u64) -> Vec<u64> {
+  let mut roots = Vec::new();
+  let mut offset = 0u64;
+  let mut remaining = length;
+
+  while remaining > 0 {
+    let block_depth = 63 - remaining.leading_zeros();
+    let block_size = 1u64 << block_depth;
+    roots.push(2 * offset + block_size - 1);
+    offset += block_size;
+    remaining -= block_size;
+  }
+
+  roots
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::HashAlgorithm;
+
+  struct MemoryStorage {
+    entry_size: u16,
+    entries: Vec<Vec<u8>>,
+  }
+
+  impl SleepStorage for MemoryStorage {
+    fn read(&mut self, index: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+      Ok(self.entries[index as usize].clone())
+    }
+
+    fn write(&mut self, index: u64, data: &[u8]) -> Result<(), Box<dyn Error>> {
+      self.entries[index as usize] = data.to_vec();
+      Ok(())
+    }
+
+    fn len(&mut self) -> Result<u64, Box<dyn Error>> {
+      Ok(self.entries.len() as u64)
+    }
+
+    fn entry_size(&self) -> u16 {
+      self.entry_size
+    }
+
+    fn hash_algorithm(&self) -> &HashAlgorithm {
+      &HashAlgorithm::BLAKE2b
+    }
+  }
+
+  fn entry(node: &Node) -> Vec<u8> {
+    let mut buffer = node.hash.clone();
+    buffer.extend_from_slice(&uint64be(node.length));
+    buffer
+  }
+
+  #[test]
+  fn test_depth() {
+    assert_eq!(depth(0), 0);
+    assert_eq!(depth(2), 0);
+    assert_eq!(depth(1), 1);
+    assert_eq!(depth(5), 1);
+    assert_eq!(depth(3), 2);
+    assert_eq!(depth(7), 3);
+  }
+
+  #[test]
+  fn test_children() {
+    assert_eq!(children(1, 1), (0, 2));
+    assert_eq!(children(3, 2), (1, 5));
+    assert_eq!(children(7, 3), (3, 11));
+  }
+
+  #[test]
+  fn test_roots() {
+    assert_eq!(roots(1), vec![0]);
+    assert_eq!(roots(2), vec![1]);
+    assert_eq!(roots(3), vec![1, 4]);
+    assert_eq!(roots(4), vec![3]);
+    assert_eq!(roots(5), vec![3, 8]);
+  }
+
+  #[test]
+  fn test_verify_node_round_trip() {
+    let left = Node {
+      hash: leaf_hash(b"left").unwrap(),
+      length: 4,
+    };
+    let right = Node {
+      hash: leaf_hash(b"right").unwrap(),
+      length: 5,
+    };
+    let parent = Node {
+      hash: parent_hash(&left, &right).unwrap(),
+      length: left.length + right.length,
+    };
+
+    let mut storage = MemoryStorage {
+      entry_size: 40,
+      entries: vec![entry(&left), entry(&parent), entry(&right)],
+    };
+
+    assert!(verify_node(&mut storage, 1).unwrap());
+
+    storage.entries[1] = entry(&Node {
+      hash: vec![0; 32],
+      length: parent.length,
+    });
+    assert!(!verify_node(&mut storage, 1).unwrap());
+  }
+}